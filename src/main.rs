@@ -1,9 +1,20 @@
-use std::{fmt, str::FromStr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use actix::{Actor, Addr, Context, Handler, Message, Recipient};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Recipient};
 use clap::Parser;
+use ed25519_dalek::Signature;
 use futures_lite::StreamExt;
-use iroh::{Endpoint, NodeAddr, NodeId, protocol::Router};
+use iroh::{
+    Endpoint, NodeAddr, NodeId, SecretKey,
+    endpoint::Connection,
+    protocol::{ProtocolHandler, Router},
+};
 use iroh_gossip::{
     ALPN,
     api::{GossipReceiver, GossipSender},
@@ -12,13 +23,30 @@ use iroh_gossip::{
 };
 use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
 };
 
 #[derive(Parser, Debug)]
 struct Args {
     #[clap(short, long, default_value = "0")]
     bind_port: u16,
+    /// Nickname announced to the room on join.
+    #[clap(short, long)]
+    nick: Option<String>,
+    /// When set, expose the room to standard IRC clients on this TCP port.
+    #[clap(long)]
+    irc_port: Option<u16>,
+    /// Flush a coalesced turn once this many messages are buffered.
+    #[clap(long, default_value = "16")]
+    batch_size: usize,
+    /// Flush a partially filled turn after this many milliseconds.
+    #[clap(long, default_value = "20")]
+    linger_ms: u64,
+    /// Persist messages and known peers to this sqlite database.
+    #[clap(long)]
+    db: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -39,7 +67,7 @@ async fn main() -> anyhow::Result<()> {
     //let system = actix::prelude::System::new();
     let args = Args::parse();
 
-    let (topic, nodes) = match &args.command {
+    let (topic, mut nodes) = match &args.command {
         Command::Open => {
             let topic = TopicId::from_bytes(rand::random());
             println!("> opening chat room for topic {topic}");
@@ -51,13 +79,49 @@ async fn main() -> anyhow::Result<()> {
             (topic, nodes)
         }
     };
+
+    // Optional persistence. When enabled, rejoin the mesh from the last-known
+    // peers and persist the ones we learn from the ticket. Peers are stored per
+    // topic and `Open` mints a fresh random topic, so only `Join` finds any.
+    let store = match &args.db {
+        Some(path) => Some(Store::open(path).await?),
+        None => None,
+    };
+    if let Some(store) = &store {
+        let known: std::collections::HashSet<NodeId> = nodes.iter().map(|n| n.node_id).collect();
+        match store.load_peers(topic).await {
+            Ok(stored) => nodes.extend(stored.into_iter().filter(|n| !known.contains(&n.node_id))),
+            Err(err) => eprintln!("> failed to load stored peers: {err}"),
+        }
+        if let Err(err) = store.save_peers(topic, &nodes).await {
+            eprintln!("> failed to persist peers: {err}");
+        }
+    }
+
     // Create Actix system manually for multi-threaded runtime
 
     //let execution = async move {
-    let endpoint = Endpoint::builder().discovery_n0().bind().await.unwrap();
+    // With persistence enabled, reuse the stored secret key so our NodeId (and
+    // therefore our persisted seq history) survives a restart; otherwise let the
+    // endpoint mint a fresh ephemeral key.
+    let mut builder = Endpoint::builder().discovery_n0();
+    if let Some(store) = &store {
+        let secret_key = match store.load_secret_key().await? {
+            Some(secret_key) => secret_key,
+            None => {
+                let secret_key = SecretKey::generate(rand::rngs::OsRng);
+                store.save_secret_key(&secret_key).await?;
+                secret_key
+            }
+        };
+        builder = builder.secret_key(secret_key);
+    }
+    let endpoint = builder.bind().await.unwrap();
     let gossip = Gossip::builder().spawn(endpoint.clone());
+    let history = History::new(topic, HISTORY_CAP, store.clone());
     let router = Router::builder(endpoint.clone())
         .accept(ALPN, gossip.clone())
+        .accept(HISTORY_ALPN, HistoryProtocol::new(history.clone()))
         .spawn();
 
     let ticket = {
@@ -67,19 +131,66 @@ async fn main() -> anyhow::Result<()> {
     };
     println!("> ticket: {ticket}");
 
-    let p2p = P2PActor::new().start();
+    let nick = args
+        .nick
+        .clone()
+        .unwrap_or_else(|| endpoint.node_id().fmt_short());
+
+    // Resume our outgoing sequence above anything we persisted before a restart.
+    let start_seq = match &store {
+        Some(store) => store
+            .max_seq(topic, endpoint.node_id())
+            .await
+            .unwrap_or(None)
+            .map_or(0, |max| max + 1),
+        None => 0,
+    };
+
+    let p2p = P2PActor::new(
+        endpoint.secret_key().clone(),
+        history.clone(),
+        args.batch_size,
+        Duration::from_millis(args.linger_ms),
+        start_seq,
+    )
+    .start();
     P2PActor::start_listener(
         p2p.clone(),
         endpoint.clone(),
         gossip,
         router.clone(),
         topic,
-        nodes,
+        nodes.clone(),
+        nick,
     )
     .await;
 
     let printer = LengthPrintActor.start();
-    p2p.do_send(Subscribe(printer.recipient()));
+    p2p.do_send(Subscribe(printer.clone().recipient()));
+    p2p.do_send(SubscribePresence(printer.recipient()));
+
+    // Optional IRC gateway: let ordinary IRC clients join the gossip room.
+    if let Some(irc_port) = args.irc_port {
+        let gateway = IrcGatewayActor::new(p2p.clone(), endpoint.node_id()).start();
+        p2p.do_send(Subscribe(gateway.clone().recipient()));
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", irc_port)).await?;
+        println!("> IRC gateway listening on 127.0.0.1:{irc_port}");
+        tokio::spawn(irc_accept_loop(listener, gateway));
+    }
+
+    // Late joiner: pull recent history from one of the ticket's nodes and
+    // replay it to our local subscribers while live gossip is already flowing;
+    // the receive path drops any message delivered by both.
+    if let Some(node) = nodes.into_iter().next() {
+        let endpoint = endpoint.clone();
+        let p2p = p2p.clone();
+        tokio::spawn(async move {
+            if let Err(err) = request_backlog(endpoint, node, p2p, HISTORY_CAP).await
+            {
+                eprintln!("> backlog replay failed: {err}");
+            }
+        });
+    }
 
     tokio::time::sleep(Duration::from_millis(10)).await;
     println!("Write something:");
@@ -88,14 +199,18 @@ async fn main() -> anyhow::Result<()> {
     let mut lines = stdin.lines();
 
     while let Ok(Some(line)) = lines.next_line().await {
-        let message = MessageBody {
-            from: Some(endpoint.node_id()),
-            text: line,
-        };
+        let message = MessageBody::unsigned(Some(endpoint.node_id()), line);
         p2p.do_send(SendMessage(message));
     }
 
     tokio::signal::ctrl_c().await.ok();
+    // Announce our departure so peers drop us from presence immediately rather
+    // than waiting for the keepalive timeout, then give the broadcast a moment
+    // to flush before tearing down the transport.
+    p2p.do_send(SendFrame(Frame::Leave {
+        node: endpoint.node_id(),
+    }));
+    tokio::time::sleep(Duration::from_millis(100)).await;
     router.shutdown().await.ok();
     Ok(())
 }
@@ -114,10 +229,360 @@ impl Handler<GotMessage> for LengthPrintActor {
     }
 }
 
+impl Handler<PresenceChanged> for LengthPrintActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: PresenceChanged, _: &mut Self::Context) -> Self::Result {
+        match msg.event {
+            PresenceEvent::Join { node, nick } => println!("* {nick} ({node}) joined"),
+            PresenceEvent::Leave { node } => println!("* {node} left"),
+        }
+        let online: Vec<String> = msg.roster.iter().map(|n| n.fmt_short()).collect();
+        println!("* online ({}): {}", online.len(), online.join(", "));
+    }
+}
+
+/// The single IRC channel the gateway presents for the gossip topic.
+const IRC_CHANNEL: &str = "#pippiroh";
+
+/// Bridges the local gossip room to standard IRC clients.
+///
+/// The actor is just another [`GotMessage`] subscriber: inbound `PRIVMSG`s
+/// become [`SendMessage`]s and incoming chat is fanned back out as `PRIVMSG`
+/// lines. Per-connection state lives here so actix serializes access to it.
+pub struct IrcGatewayActor {
+    p2p: Addr<P2PActor>,
+    node_id: NodeId,
+    clients: HashMap<usize, IrcClient>,
+}
+
+struct IrcClient {
+    /// Outbound line sink; the connection's writer task drains it.
+    tx: mpsc::UnboundedSender<String>,
+    nick: Option<String>,
+    user_seen: bool,
+    registered: bool,
+    joined: bool,
+}
+
+impl IrcGatewayActor {
+    fn new(p2p: Addr<P2PActor>, node_id: NodeId) -> Self {
+        Self {
+            p2p,
+            node_id,
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for IrcGatewayActor {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct RegisterClient {
+    id: usize,
+    tx: mpsc::UnboundedSender<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct DeregisterClient {
+    id: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct IrcLine {
+    id: usize,
+    line: String,
+}
+
+impl Handler<RegisterClient> for IrcGatewayActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterClient, _: &mut Self::Context) {
+        self.clients.insert(
+            msg.id,
+            IrcClient {
+                tx: msg.tx,
+                nick: None,
+                user_seen: false,
+                registered: false,
+                joined: false,
+            },
+        );
+    }
+}
+
+impl Handler<DeregisterClient> for IrcGatewayActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeregisterClient, _: &mut Self::Context) {
+        self.clients.remove(&msg.id);
+    }
+}
+
+impl Handler<IrcLine> for IrcGatewayActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: IrcLine, _: &mut Self::Context) {
+        let (command, params) = parse_irc(&msg.line);
+
+        // PRIVMSG is handled separately because it both broadcasts to gossip and
+        // fans out to the other local sockets, which needs a shared (not
+        // exclusive) borrow of `clients`.
+        if command == "PRIVMSG" {
+            if let Some(text) = params.get(1).cloned() {
+                let body = MessageBody::unsigned(Some(self.node_id), text.clone());
+                self.p2p.do_send(SendMessage(body));
+                // Gossip never loops a message back to its origin node, so
+                // deliver it to the other local clients ourselves.
+                let nick = self
+                    .clients
+                    .get(&msg.id)
+                    .and_then(|c| c.nick.clone())
+                    .unwrap_or_else(|| "*".to_string());
+                let line = format!(":{nick}!{nick}@pippiroh PRIVMSG {IRC_CHANNEL} :{text}");
+                for (id, client) in self.clients.iter() {
+                    if *id != msg.id && client.joined {
+                        let _ = client.tx.send(line.clone());
+                    }
+                }
+            }
+            return;
+        }
+
+        let Some(client) = self.clients.get_mut(&msg.id) else {
+            return;
+        };
+        match command.as_str() {
+            "NICK" => {
+                client.nick = params.first().cloned();
+                Self::maybe_welcome(client);
+            }
+            "USER" => {
+                client.user_seen = true;
+                Self::maybe_welcome(client);
+            }
+            "JOIN" => {
+                client.joined = true;
+                let nick = client.nick.clone().unwrap_or_else(|| "*".to_string());
+                let _ = client.tx.send(format!(":{nick} JOIN {IRC_CHANNEL}"));
+                let _ = client
+                    .tx
+                    .send(format!(":pippiroh 366 {nick} {IRC_CHANNEL} :End of /NAMES list"));
+            }
+            "PART" => {
+                client.joined = false;
+                let nick = client.nick.clone().unwrap_or_else(|| "*".to_string());
+                let _ = client.tx.send(format!(":{nick} PART {IRC_CHANNEL}"));
+            }
+            "PING" => {
+                let token = params.first().cloned().unwrap_or_default();
+                let _ = client.tx.send(format!("PONG :{token}"));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Handler<GotMessage> for IrcGatewayActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: GotMessage, _: &mut Self::Context) {
+        let body = msg.0;
+        let nick = body
+            .from
+            .map(|n| n.fmt_short())
+            .unwrap_or_else(|| "unknown".to_string());
+        let line = format!(":{nick}!{nick}@pippiroh PRIVMSG {IRC_CHANNEL} :{}", body.text);
+        for client in self.clients.values() {
+            if client.joined {
+                let _ = client.tx.send(line.clone());
+            }
+        }
+    }
+}
+
+impl IrcGatewayActor {
+    /// Send the welcome burst once the client has supplied both NICK and USER.
+    fn maybe_welcome(client: &mut IrcClient) {
+        if client.registered {
+            return;
+        }
+        let (Some(nick), true) = (client.nick.clone(), client.user_seen) else {
+            return;
+        };
+        client.registered = true;
+        let _ = client
+            .tx
+            .send(format!(":pippiroh 001 {nick} :Welcome to the pippiroh gateway, {nick}"));
+    }
+}
+
+/// Accept IRC connections and wire each one to `gateway`.
+async fn irc_accept_loop(listener: TcpListener, gateway: Addr<IrcGatewayActor>) {
+    let mut next_id = 0usize;
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let id = next_id;
+        next_id += 1;
+        tokio::spawn(handle_irc_client(id, stream, gateway.clone()));
+    }
+}
+
+/// Drive one IRC connection: forward inbound lines to the gateway and write
+/// outbound lines from the gateway back to the socket.
+async fn handle_irc_client(
+    id: usize,
+    stream: tokio::net::TcpStream,
+    gateway: Addr<IrcGatewayActor>,
+) {
+    let (read, mut write) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    gateway.do_send(RegisterClient { id, tx });
+
+    let writer = tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if write.write_all(line.as_bytes()).await.is_err() || write.write_all(b"\r\n").await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        gateway.do_send(IrcLine { id, line });
+    }
+
+    gateway.do_send(DeregisterClient { id });
+    writer.abort();
+}
+
+/// Parse a single IRC protocol line into `(COMMAND, params)`, dropping any
+/// prefix and treating a `:`-prefixed argument as the trailing parameter.
+fn parse_irc(line: &str) -> (String, Vec<String>) {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+    if let Some(stripped) = rest.strip_prefix(':') {
+        match stripped.find(' ') {
+            Some(idx) => rest = &stripped[idx + 1..],
+            None => return (String::new(), Vec::new()),
+        }
+    }
+    let (head, trailing) = match rest.find(" :") {
+        Some(idx) => (&rest[..idx], Some(rest[idx + 2..].to_string())),
+        None => (rest, None),
+    };
+    let mut parts = head.split_whitespace();
+    let command = parts.next().unwrap_or_default().to_uppercase();
+    let mut params: Vec<String> = parts.map(str::to_string).collect();
+    if let Some(trailing) = trailing {
+        params.push(trailing);
+    }
+    (command, params)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct MessageBody {
     from: Option<NodeId>,
+    /// Per-sender monotonically increasing sequence number, used for ordering
+    /// and replay protection.
+    seq: u64,
+    /// Milliseconds since the unix epoch, stamped by the sender.
+    timestamp: u64,
     text: String,
+    /// Ed25519 signature over [`MessageBody::signing_bytes`], produced with the
+    /// sender's iroh secret key.
+    #[serde(with = "serde_signature")]
+    signature: [u8; 64],
+}
+
+impl MessageBody {
+    /// Create a message that has not been signed yet; `seq`, `timestamp` and
+    /// `signature` are filled in by [`P2PActor`] just before it broadcasts.
+    fn unsigned(from: Option<NodeId>, text: String) -> Self {
+        Self {
+            from,
+            seq: 0,
+            timestamp: 0,
+            text,
+            signature: [0u8; 64],
+        }
+    }
+
+    /// The canonical bytes that are signed and verified: `(from, seq, timestamp, text)`.
+    fn signing_bytes(&self) -> Vec<u8> {
+        bincode::serde::encode_to_vec(
+            (&self.from, self.seq, self.timestamp, &self.text),
+            BINCODE_CONFIG,
+        )
+        .expect("bincode::encode_to_vec is infallible")
+    }
+
+    /// Verify the signature against the `from` public key. Returns `false` when
+    /// the message is unsigned (`from` is `None`) or the signature is invalid.
+    fn verify(&self) -> bool {
+        let Some(from) = self.from else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&self.signature);
+        from.verify(&self.signing_bytes(), &signature).is_ok()
+    }
+}
+
+/// Serialize a fixed 64-byte signature as a plain byte sequence so the wire form
+/// stays compact under bincode.
+mod serde_signature {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 64], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 64], D::Error> {
+        let bytes = <Vec<u8>>::deserialize(deserializer)?;
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("signature must be 64 bytes"))
+    }
+}
+
+/// Envelope for everything that travels over the gossip transport. The
+/// encoding carries no version field; variants are told apart only by their
+/// bincode variant index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Frame {
+    Chat(MessageBody),
+    /// A batch of chat messages coalesced into a single broadcast; delivered
+    /// in order on receipt.
+    Turn(Vec<MessageBody>),
+    Join { node: NodeId, nick: String },
+    Leave { node: NodeId },
+    /// Keepalive. The gossip transport has no unicast channel, so we diverge
+    /// from a request/response ping: each node simply broadcasts its own
+    /// `Ping` on a timer and observing any peer's `Ping` refreshes its
+    /// liveness. There is deliberately no `Pong` — replying would turn every
+    /// keepalive into an O(N^2) fan-out.
+    Ping { nonce: u64 },
+}
+
+/// A membership transition reported to presence subscribers, carrying the
+/// up-to-date roster at the time of the event.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct PresenceChanged {
+    event: PresenceEvent,
+    roster: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+enum PresenceEvent {
+    Join { node: NodeId, nick: String },
+    Leave { node: NodeId },
 }
 
 #[derive(Message, Clone)]
@@ -128,31 +593,100 @@ pub struct GotMessage(MessageBody);
 #[rtype(result = "()")]
 pub struct SendMessage(MessageBody);
 
+/// Broadcast a non-chat control frame (`Join`/`Leave`/`Ping`).
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+struct SendFrame(Frame);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 struct Subscribe(pub Recipient<GotMessage>);
 
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SubscribePresence(pub Recipient<PresenceChanged>);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 struct SetSender(GossipSender);
 
 pub struct P2PActor {
     subscribers: Vec<Recipient<GotMessage>>,
+    presence_subscribers: Vec<Recipient<PresenceChanged>>,
     sender: Option<GossipSender>,
+    secret_key: SecretKey,
+    /// Next outgoing sequence number for messages we originate.
+    next_seq: u64,
+    /// Monotonic nonce for keepalive pings.
+    next_ping: u64,
+    /// Recently accepted seqs per peer, for replay/duplicate suppression that
+    /// tolerates the out-of-order delivery gossip may produce.
+    seen: HashMap<NodeId, SeenSet>,
+    /// Bounded history of accepted messages, served to late joiners.
+    history: History,
+    /// Outgoing messages awaiting a coalesced flush.
+    pending: Vec<MessageBody>,
+    /// Flush `pending` once it reaches this many messages.
+    batch_size: usize,
+    /// Flush `pending` at most this long after the first buffered message.
+    linger: Duration,
 }
 
 impl P2PActor {
-    pub fn new() -> Self {
+    pub fn new(
+        secret_key: SecretKey,
+        history: History,
+        batch_size: usize,
+        linger: Duration,
+        start_seq: u64,
+    ) -> Self {
         Self {
             subscribers: Default::default(),
+            presence_subscribers: Default::default(),
             sender: None,
+            secret_key,
+            // Resume our sequence above any persisted message so long-lived
+            // peers do not drop our post-restart traffic as a replay.
+            next_seq: start_seq,
+            next_ping: 0,
+            seen: HashMap::new(),
+            history,
+            pending: Vec::new(),
+            batch_size: batch_size.max(1),
+            linger,
+        }
+    }
+
+    /// Broadcast and clear any buffered outgoing messages.
+    fn flush_pending(&mut self) {
+        if self.pending.is_empty() {
+            return;
         }
+        let batch = std::mem::take(&mut self.pending);
+        // A single message still goes out as a plain `Chat` frame to keep the
+        // common, non-bursty case maximally cheap.
+        let frame = if batch.len() == 1 {
+            Frame::Chat(batch.into_iter().next().unwrap())
+        } else {
+            Frame::Turn(batch)
+        };
+        self.broadcast_frame(frame);
+    }
+
+    /// Encode and broadcast a frame on the gossip transport.
+    fn broadcast_frame(&self, frame: Frame) {
+        let sender = self.sender.as_ref().expect("no P2P sender").clone();
+        let bytes = bincode::serde::encode_to_vec(&frame, BINCODE_CONFIG).unwrap();
+        tokio::spawn(async move {
+            sender.broadcast(bytes.into()).await.unwrap();
+        });
     }
 
     pub fn subscribe(&mut self, recipient: Recipient<GotMessage>) {
         self.subscribers.push(recipient);
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn start_listener(
         addr: Addr<Self>,
         endpoint: Endpoint,
@@ -160,6 +694,7 @@ impl P2PActor {
         router: Router,
         topic: TopicId,
         nodes: Vec<NodeAddr>,
+        nick: String,
     ) {
         let node_ids = nodes.iter().map(|p| p.node_id).collect();
         if nodes.is_empty() {
@@ -176,27 +711,136 @@ impl P2PActor {
 
         addr.do_send(SetSender(sender));
 
+        // Announce ourselves to the room.
+        addr.do_send(SendFrame(Frame::Join {
+            node: endpoint.node_id(),
+            nick,
+        }));
+
         tokio::spawn(P2PActor::subscribe_loop(addr, receiver));
     }
 
     async fn subscribe_loop(addr: Addr<Self>, mut receiver: GossipReceiver) {
+        let mut last_seen: HashMap<NodeId, Instant> = HashMap::new();
+        let mut nicks: HashMap<NodeId, String> = HashMap::new();
+        let mut ticker = tokio::time::interval(PRESENCE_TICK);
         loop {
-            let Ok(Some(event)) = receiver.try_next().await else {
-                continue;
-            };
-            match event {
-                iroh_gossip::api::Event::Received(message) => {
-                    let Ok((message, _)) =
-                        bincode::serde::decode_from_slice(&message.content, BINCODE_CONFIG)
-                    else {
-                        continue;
-                    };
-                    addr.do_send(GotMessage(message));
+            tokio::select! {
+                event = receiver.next() => {
+                    let Some(Ok(event)) = event else { continue };
+                    if let iroh_gossip::api::Event::Received(message) = event {
+                        let Ok((frame, _)): Result<(Frame, _), _> =
+                            bincode::serde::decode_from_slice(&message.content, BINCODE_CONFIG)
+                        else {
+                            continue;
+                        };
+                        Self::handle_frame(
+                            &addr,
+                            frame,
+                            message.delivered_from,
+                            &mut last_seen,
+                            &mut nicks,
+                        );
+                    }
+                }
+                _ = ticker.tick() => {
+                    Self::expire_silent(&addr, &mut last_seen, &mut nicks);
+                }
+            }
+        }
+    }
+
+    /// Dispatch one decoded frame, updating liveness and presence state.
+    fn handle_frame(
+        addr: &Addr<Self>,
+        frame: Frame,
+        delivered_from: NodeId,
+        last_seen: &mut HashMap<NodeId, Instant>,
+        nicks: &mut HashMap<NodeId, String>,
+    ) {
+        match frame {
+            Frame::Chat(body) => {
+                if let Some(from) = body.from {
+                    last_seen.insert(from, Instant::now());
+                }
+                addr.do_send(GotMessage(body));
+            }
+            Frame::Turn(bodies) => {
+                for body in bodies {
+                    if let Some(from) = body.from {
+                        last_seen.insert(from, Instant::now());
+                    }
+                    addr.do_send(GotMessage(body));
+                }
+            }
+            Frame::Join { node, nick } => {
+                let known = last_seen.insert(node, Instant::now()).is_some();
+                nicks.insert(node, nick.clone());
+                if !known {
+                    Self::emit_presence(
+                        addr,
+                        PresenceEvent::Join { node, nick },
+                        last_seen,
+                    );
+                }
+            }
+            Frame::Leave { node } => {
+                last_seen.remove(&node);
+                nicks.remove(&node);
+                Self::emit_presence(addr, PresenceEvent::Leave { node }, last_seen);
+            }
+            // Each node broadcasts its own keepalive, so simply observing a
+            // peer's `Ping` is enough to refresh its liveness. If we learn of a
+            // peer this way before ever seeing its `Join` (it joined before us,
+            // or the `Join` was missed), surface a synthetic join so presence
+            // still reflects the roster.
+            Frame::Ping { .. } => {
+                let known = last_seen.insert(delivered_from, Instant::now()).is_some();
+                if !known {
+                    let nick = nicks
+                        .get(&delivered_from)
+                        .cloned()
+                        .unwrap_or_else(|| delivered_from.fmt_short());
+                    Self::emit_presence(
+                        addr,
+                        PresenceEvent::Join {
+                            node: delivered_from,
+                            nick,
+                        },
+                        last_seen,
+                    );
                 }
-                _ => continue,
             }
         }
     }
+
+    /// Emit a synthetic `Leave` for peers that have been silent past the timeout.
+    fn expire_silent(
+        addr: &Addr<Self>,
+        last_seen: &mut HashMap<NodeId, Instant>,
+        nicks: &mut HashMap<NodeId, String>,
+    ) {
+        let now = Instant::now();
+        let expired: Vec<NodeId> = last_seen
+            .iter()
+            .filter(|(_, seen)| now.duration_since(**seen) > PRESENCE_TIMEOUT)
+            .map(|(node, _)| *node)
+            .collect();
+        for node in expired {
+            last_seen.remove(&node);
+            nicks.remove(&node);
+            Self::emit_presence(addr, PresenceEvent::Leave { node }, last_seen);
+        }
+    }
+
+    fn emit_presence(
+        addr: &Addr<Self>,
+        event: PresenceEvent,
+        last_seen: &HashMap<NodeId, Instant>,
+    ) {
+        let roster = last_seen.keys().copied().collect();
+        addr.do_send(PresenceChanged { event, roster });
+    }
 }
 
 impl Handler<Subscribe> for P2PActor {
@@ -207,11 +851,43 @@ impl Handler<Subscribe> for P2PActor {
     }
 }
 
+impl Handler<SubscribePresence> for P2PActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribePresence, _: &mut Context<Self>) {
+        self.presence_subscribers.push(msg.0);
+    }
+}
+
 impl Handler<SetSender> for P2PActor {
     type Result = ();
 
-    fn handle(&mut self, msg: SetSender, _: &mut Self::Context) -> Self::Result {
-        self.sender = Some(msg.0)
+    fn handle(&mut self, msg: SetSender, ctx: &mut Self::Context) -> Self::Result {
+        self.sender = Some(msg.0);
+        // Start the keepalive once we can broadcast.
+        ctx.run_interval(PING_INTERVAL, |actor, _| {
+            let nonce = actor.next_ping;
+            actor.next_ping += 1;
+            actor.broadcast_frame(Frame::Ping { nonce });
+        });
+    }
+}
+
+impl Handler<SendFrame> for P2PActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendFrame, _: &mut Self::Context) -> Self::Result {
+        self.broadcast_frame(msg.0);
+    }
+}
+
+impl Handler<PresenceChanged> for P2PActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: PresenceChanged, _: &mut Self::Context) -> Self::Result {
+        self.presence_subscribers
+            .iter()
+            .for_each(|s| s.do_send(msg.clone()).unwrap());
     }
 }
 
@@ -219,6 +895,26 @@ impl Handler<GotMessage> for P2PActor {
     type Result = ();
 
     fn handle(&mut self, msg: GotMessage, _: &mut Self::Context) -> Self::Result {
+        let body = &msg.0;
+        // Reject unsigned or forged messages outright.
+        let Some(from) = body.from else {
+            return;
+        };
+        if !body.verify() {
+            return;
+        }
+        // Replay protection: drop sequences already seen from this sender,
+        // while still admitting ones that merely arrive out of order.
+        if !self
+            .seen
+            .entry(from)
+            .or_insert_with(|| SeenSet::new(SEEN_WINDOW))
+            .observe(body.seq)
+        {
+            return;
+        }
+        self.history.record(body);
+
         self.subscribers
             .iter()
             .for_each(|s| s.do_send(msg.clone()).unwrap());
@@ -228,19 +924,545 @@ impl Handler<GotMessage> for P2PActor {
 impl Handler<SendMessage> for P2PActor {
     type Result = ();
 
-    fn handle(&mut self, msg: SendMessage, _: &mut Self::Context) -> Self::Result {
-        let sender = self.sender.as_ref().expect("no P2P sender").clone();
-        let bytes = bincode::serde::encode_to_vec(msg.0, BINCODE_CONFIG).unwrap();
-        tokio::spawn(async move {
-            sender.broadcast(bytes.into()).await.unwrap();
-        });
+    fn handle(&mut self, msg: SendMessage, ctx: &mut Self::Context) -> Self::Result {
+        // Assign a contiguous seq, sign, and buffer; the batch is flushed once
+        // it is full or the linger timer fires, whichever comes first.
+        let mut body = msg.0;
+        body.seq = self.next_seq;
+        self.next_seq += 1;
+        body.timestamp = now_millis();
+        let signature = self.secret_key.sign(&body.signing_bytes());
+        body.signature = signature.to_bytes();
+        self.history.record(&body);
+
+        let was_empty = self.pending.is_empty();
+        self.pending.push(body);
+        if self.pending.len() >= self.batch_size {
+            self.flush_pending();
+        } else if was_empty {
+            // Arm the linger timer on the first buffered message so the batch
+            // is flushed at most `linger` after it, not on a fixed cadence.
+            ctx.run_later(self.linger, |actor, _| actor.flush_pending());
+        }
+    }
+}
+
+/// How often to broadcast a keepalive ping.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often `subscribe_loop` scans for silent peers.
+const PRESENCE_TICK: Duration = Duration::from_secs(2);
+
+/// A peer is considered gone after this long without any frame from it.
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How many recent sequence numbers to remember per sender for replay
+/// suppression.
+const SEEN_WINDOW: usize = 256;
+
+/// A bounded, insertion-ordered set of the sequence numbers most recently
+/// accepted from a single sender.
+///
+/// A single high-water mark would reject any seq below the maximum, but gossip
+/// can deliver a later batch before an earlier one — so a strict `seq <= last`
+/// check permanently drops the messages that arrive late. Tracking a window of
+/// seen seqs instead lets out-of-order delivery through while still
+/// suppressing genuine replays and duplicates.
+struct SeenSet {
+    cap: usize,
+    order: VecDeque<u64>,
+    set: HashSet<u64>,
+}
+
+impl SeenSet {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap: cap.max(1),
+            order: VecDeque::new(),
+            set: HashSet::new(),
+        }
+    }
+
+    /// Record `seq` as seen, returning `true` if it is new (and should be
+    /// accepted) or `false` if it is a replay of something already in the
+    /// window.
+    fn observe(&mut self, seq: u64) -> bool {
+        if !self.set.insert(seq) {
+            return false;
+        }
+        self.order.push_back(seq);
+        if self.order.len() > self.cap {
+            if let Some(old) = self.order.pop_front() {
+                self.set.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// Milliseconds since the unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// ALPN for the direct backlog-replay protocol dialed on join.
+pub const HISTORY_ALPN: &[u8] = b"pippiroh/history/0";
+
+/// How many recent messages to retain and replay by default.
+pub const HISTORY_CAP: usize = 128;
+
+/// A bounded, cloneable ring buffer of accepted messages for a single topic.
+///
+/// Both [`P2PActor`] (writer) and [`HistoryProtocol`] (reader) hold a handle,
+/// so it is shared behind an `Arc<Mutex<_>>`.
+#[derive(Clone)]
+pub struct History {
+    inner: Arc<Mutex<HistoryInner>>,
+    /// When present, history is also persisted to and served from sqlite.
+    store: Option<Store>,
+}
+
+struct HistoryInner {
+    topic: TopicId,
+    cap: usize,
+    buf: VecDeque<MessageBody>,
+}
+
+impl History {
+    fn new(topic: TopicId, cap: usize, store: Option<Store>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HistoryInner {
+                topic,
+                cap,
+                buf: VecDeque::with_capacity(cap),
+            })),
+            store,
+        }
+    }
+
+    /// Append a message, dropping the oldest entry once the cap is reached.
+    /// Duplicates (same `from`/`seq`) are ignored so our own sends and gossip
+    /// echoes do not pile up. When a store is configured the insert is spawned
+    /// so it never blocks the actor thread.
+    fn record(&self, body: &MessageBody) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner
+            .buf
+            .iter()
+            .any(|m| m.from == body.from && m.seq == body.seq)
+        {
+            return;
+        }
+        if inner.buf.len() == inner.cap {
+            inner.buf.pop_front();
+        }
+        inner.buf.push_back(body.clone());
+        let topic = inner.topic;
+        drop(inner);
+
+        if let Some(store) = self.store.clone() {
+            let body = body.clone();
+            tokio::spawn(async move {
+                if let Err(err) = store.insert_message(topic, body).await {
+                    eprintln!("> failed to persist message: {err}");
+                }
+            });
+        }
+    }
+
+    /// Answer a history query, oldest entry first so replay preserves order.
+    /// Served from sqlite when a store is configured, otherwise from the ring.
+    async fn query(&self, req: &HistoryRequest) -> Vec<MessageBody> {
+        if let Some(store) = &self.store {
+            match store.query_history(self.topic(), req).await {
+                Ok(rows) => return rows,
+                Err(err) => eprintln!("> failed to read history from store: {err}"),
+            }
+        }
+        self.query_memory(req)
+    }
+
+    fn query_memory(&self, req: &HistoryRequest) -> Vec<MessageBody> {
+        let inner = self.inner.lock().unwrap();
+        let mut out: Vec<MessageBody> = inner
+            .buf
+            .iter()
+            .filter(|m| req.matches(m))
+            .cloned()
+            .collect();
+        // Keep the newest `limit` entries, still ordered oldest-first.
+        if out.len() > req.limit {
+            out.drain(0..out.len() - req.limit);
+        }
+        out
+    }
+
+    fn topic(&self) -> TopicId {
+        self.inner.lock().unwrap().topic
+    }
+}
+
+/// A backlog query. With `before == None` it asks for the latest `limit`
+/// messages across all senders; otherwise for up to `limit` messages from a
+/// single sender with a smaller `seq`.
+///
+/// The `before` cursor is scoped to one sender because `seq` is per-sender —
+/// a bare global `seq` would mix unrelated senders' sequence spaces.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    before: Option<Cursor>,
+    limit: usize,
+}
+
+/// A per-sender pagination cursor: messages from `from` with `seq < seq`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cursor {
+    from: NodeId,
+    seq: u64,
+}
+
+impl HistoryRequest {
+    /// The newest `limit` messages in the buffer.
+    fn latest(limit: usize) -> Self {
+        Self { before: None, limit }
+    }
+
+    /// Up to `limit` messages from `cursor`'s sender with a smaller `seq`,
+    /// used to page further back than the first `latest` batch reached.
+    fn before(cursor: Cursor, limit: usize) -> Self {
+        Self {
+            before: Some(cursor),
+            limit,
+        }
+    }
+
+    /// Does `body` fall before this query's cursor?
+    fn matches(&self, body: &MessageBody) -> bool {
+        match &self.before {
+            Some(cursor) => body.from == Some(cursor.from) && body.seq < cursor.seq,
+            None => true,
+        }
+    }
+}
+
+/// iroh protocol that serves [`History`] entries over a direct QUIC stream.
+#[derive(Clone, Debug)]
+pub struct HistoryProtocol {
+    history: History,
+}
+
+impl std::fmt::Debug for History {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("History")
+            .field("topic", &self.topic())
+            .finish()
+    }
+}
+
+impl HistoryProtocol {
+    fn new(history: History) -> Self {
+        Self { history }
     }
 }
 
+impl ProtocolHandler for HistoryProtocol {
+    async fn accept(&self, connection: Connection) -> anyhow::Result<()> {
+        let (mut send, mut recv) = connection.accept_bi().await?;
+
+        // The client writes a single request and finishes its side.
+        let req_bytes = recv.read_to_end(64 * 1024).await?;
+        let (req, _): (HistoryRequest, _) =
+            bincode::serde::decode_from_slice(&req_bytes, BINCODE_CONFIG)?;
+
+        for body in self.history.query(&req).await {
+            write_frame(&mut send, &body).await?;
+        }
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+/// How many pages of backlog to pull before giving up, so a long-lived room
+/// cannot stall a joiner indefinitely.
+const MAX_BACKLOG_PAGES: usize = 8;
+
+/// Dial `node` over the history ALPN and replay its backlog to local
+/// subscribers, newest page first and then paging backwards with a per-sender
+/// cursor. Every message is routed through the normal receive path, which
+/// verifies signatures and drops duplicates already delivered from gossip.
+async fn request_backlog(
+    endpoint: Endpoint,
+    node: NodeAddr,
+    addr: Addr<P2PActor>,
+    limit: usize,
+) -> anyhow::Result<()> {
+    let mut req = HistoryRequest::latest(limit);
+    for _ in 0..MAX_BACKLOG_PAGES {
+        // A fresh connection per page: the server answers exactly one request
+        // per accepted connection.
+        let connection = endpoint.connect(node.clone(), HISTORY_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+
+        let req_bytes = bincode::serde::encode_to_vec(&req, BINCODE_CONFIG)?;
+        send.write_all(&req_bytes).await?;
+        send.finish()?;
+
+        // Batches arrive oldest-first, so the first frame pins the cursor for
+        // the next page back.
+        let mut oldest: Option<Cursor> = None;
+        let mut count = 0usize;
+        while let Some(body) = read_frame(&mut recv).await? {
+            if oldest.is_none() {
+                if let Some(from) = body.from {
+                    oldest = Some(Cursor {
+                        from,
+                        seq: body.seq,
+                    });
+                }
+            }
+            count += 1;
+            addr.do_send(GotMessage(body));
+        }
+        connection.close(0u32.into(), b"done");
+
+        // Stop once a page comes back short (the sender is exhausted) or the
+        // oldest entry is already at the start of its sequence space.
+        match oldest {
+            Some(cursor) if count >= limit && cursor.seq > 0 => {
+                req = HistoryRequest::before(cursor, limit);
+            }
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// Length-delimited write of one bincode-encoded [`MessageBody`].
+async fn write_frame(
+    send: &mut iroh::endpoint::SendStream,
+    body: &MessageBody,
+) -> anyhow::Result<()> {
+    let bytes = bincode::serde::encode_to_vec(body, BINCODE_CONFIG)?;
+    send.write_all(&(bytes.len() as u32).to_le_bytes()).await?;
+    send.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Read one length-delimited [`MessageBody`], or `None` at end of stream.
+async fn read_frame(
+    recv: &mut iroh::endpoint::RecvStream,
+) -> anyhow::Result<Option<MessageBody>> {
+    let mut len_buf = [0u8; 4];
+    match recv.read_exact(&mut len_buf).await {
+        Ok(()) => {}
+        Err(_) => return Ok(None),
+    }
+    let len = u32::from_le_bytes(len_buf) as usize;
+    let mut buf = vec![0u8; len];
+    recv.read_exact(&mut buf).await?;
+    let (body, _) = bincode::serde::decode_from_slice(&buf, BINCODE_CONFIG)?;
+    Ok(Some(body))
+}
+
 impl Actor for P2PActor {
     type Context = Context<Self>;
 }
 
+/// Optional sqlite-backed persistence for messages and known peers.
+///
+/// A handle is cheap to clone (the underlying `SqlitePool` is shared), so both
+/// the history subsystem and `main` hold one. Absent the `--db` flag the whole
+/// feature is simply never constructed and the node stays in-memory.
+#[derive(Clone)]
+pub struct Store {
+    pool: sqlx::SqlitePool,
+}
+
+impl Store {
+    /// Open (creating if needed) the database and ensure the schema exists.
+    async fn open(path: &str) -> anyhow::Result<Self> {
+        let options = sqlx::sqlite::SqliteConnectOptions::from_str(path)?.create_if_missing(true);
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect_with(options)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS messages (
+                topic TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                timestamp INTEGER NOT NULL,
+                from_id TEXT,
+                text TEXT NOT NULL,
+                signature BLOB NOT NULL,
+                PRIMARY KEY (topic, from_id, seq)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS peers (
+                topic TEXT NOT NULL,
+                node_id TEXT NOT NULL,
+                addr BLOB NOT NULL,
+                PRIMARY KEY (topic, node_id)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value BLOB NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        Ok(Self { pool })
+    }
+
+    /// Persist one accepted message. Duplicates are ignored.
+    async fn insert_message(&self, topic: TopicId, body: MessageBody) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO messages (topic, seq, timestamp, from_id, text, signature)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(topic.to_string())
+        .bind(body.seq as i64)
+        .bind(body.timestamp as i64)
+        .bind(body.from.map(|f| f.to_string()))
+        .bind(body.text)
+        .bind(body.signature.to_vec())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Persist our node identity so the same `NodeId` is reused across restarts.
+    async fn save_secret_key(&self, secret_key: &SecretKey) -> anyhow::Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO meta (key, value) VALUES ('secret_key', ?)")
+            .bind(secret_key.to_bytes().to_vec())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Load our persisted node identity, if one was stored.
+    async fn load_secret_key(&self) -> anyhow::Result<Option<SecretKey>> {
+        use sqlx::Row;
+        let row = sqlx::query("SELECT value FROM meta WHERE key = 'secret_key'")
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let bytes: Vec<u8> = row.try_get("value")?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("stored secret key is not 32 bytes"))?;
+        Ok(Some(SecretKey::from_bytes(&bytes)))
+    }
+
+    /// Remember the peer addresses for a topic so a restart can rejoin the mesh.
+    async fn save_peers(&self, topic: TopicId, nodes: &[NodeAddr]) -> anyhow::Result<()> {
+        for node in nodes {
+            let addr = bincode::serde::encode_to_vec(node, BINCODE_CONFIG)?;
+            sqlx::query("INSERT OR REPLACE INTO peers (topic, node_id, addr) VALUES (?, ?, ?)")
+                .bind(topic.to_string())
+                .bind(node.node_id.to_string())
+                .bind(addr)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Load the last-known peer addresses for a topic.
+    async fn load_peers(&self, topic: TopicId) -> anyhow::Result<Vec<NodeAddr>> {
+        use sqlx::Row;
+        let rows = sqlx::query("SELECT addr FROM peers WHERE topic = ?")
+            .bind(topic.to_string())
+            .fetch_all(&self.pool)
+            .await?;
+        let mut out = Vec::with_capacity(rows.len());
+        for row in rows {
+            let addr: Vec<u8> = row.try_get("addr")?;
+            let (node, _) = bincode::serde::decode_from_slice(&addr, BINCODE_CONFIG)?;
+            out.push(node);
+        }
+        Ok(out)
+    }
+
+    /// The highest `seq` persisted for a given sender on a topic, if any.
+    async fn max_seq(&self, topic: TopicId, from: NodeId) -> anyhow::Result<Option<u64>> {
+        use sqlx::Row;
+        let row = sqlx::query("SELECT MAX(seq) AS max_seq FROM messages WHERE topic = ? AND from_id = ?")
+            .bind(topic.to_string())
+            .bind(from.to_string())
+            .fetch_one(&self.pool)
+            .await?;
+        let max: Option<i64> = row.try_get("max_seq")?;
+        Ok(max.map(|m| m as u64))
+    }
+
+    /// Serve a backlog query from the database, oldest entry first.
+    async fn query_history(
+        &self,
+        topic: TopicId,
+        req: &HistoryRequest,
+    ) -> anyhow::Result<Vec<MessageBody>> {
+        use sqlx::Row;
+        let topic = topic.to_string();
+        let rows = match &req.before {
+            // Per-sender cursor: page within one sender's own `seq` space.
+            Some(cursor) => {
+                sqlx::query(
+                    "SELECT seq, timestamp, from_id, text, signature FROM messages
+                     WHERE topic = ? AND from_id = ? AND seq < ? ORDER BY seq DESC LIMIT ?",
+                )
+                .bind(topic)
+                .bind(cursor.from.to_string())
+                .bind(cursor.seq as i64)
+                .bind(req.limit as i64)
+                .fetch_all(&self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query(
+                    "SELECT seq, timestamp, from_id, text, signature FROM messages
+                     WHERE topic = ? ORDER BY timestamp DESC, from_id DESC, seq DESC LIMIT ?",
+                )
+                .bind(topic)
+                .bind(req.limit as i64)
+                .fetch_all(&self.pool)
+                .await?
+            }
+        };
+
+        let mut out = Vec::with_capacity(rows.len());
+        for row in rows {
+            let from_id: Option<String> = row.try_get("from_id")?;
+            let from = from_id.as_deref().and_then(|s| NodeId::from_str(s).ok());
+            let signature: Vec<u8> = row.try_get("signature")?;
+            let signature = signature
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("stored signature is not 64 bytes"))?;
+            out.push(MessageBody {
+                from,
+                seq: row.try_get::<i64, _>("seq")? as u64,
+                timestamp: row.try_get::<i64, _>("timestamp")? as u64,
+                text: row.try_get("text")?,
+                signature,
+            });
+        }
+        // Stored newest-first; flip to oldest-first for in-order replay.
+        out.reverse();
+        Ok(out)
+    }
+}
+
 pub const BINCODE_CONFIG: bincode::config::Configuration = bincode::config::standard();
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -277,3 +1499,115 @@ impl FromStr for Ticket {
         Self::from_bytes(&bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed(secret: &SecretKey, seq: u64, text: &str) -> MessageBody {
+        let mut body = MessageBody::unsigned(Some(secret.public()), text.to_string());
+        body.seq = seq;
+        body.timestamp = 1;
+        body.signature = secret.sign(&body.signing_bytes()).to_bytes();
+        body
+    }
+
+    #[test]
+    fn verify_accepts_valid_signature() {
+        let secret = SecretKey::from_bytes(&[7u8; 32]);
+        assert!(signed(&secret, 0, "hello").verify());
+    }
+
+    #[test]
+    fn verify_rejects_forged_and_unsigned() {
+        let secret = SecretKey::from_bytes(&[7u8; 32]);
+
+        // Tampering with the text after signing invalidates the signature.
+        let mut forged = signed(&secret, 0, "hello");
+        forged.text = "goodbye".to_string();
+        assert!(!forged.verify());
+
+        // A message claiming a different author does not verify.
+        let other = SecretKey::from_bytes(&[9u8; 32]);
+        let mut impersonated = signed(&secret, 0, "hello");
+        impersonated.from = Some(other.public());
+        assert!(!impersonated.verify());
+
+        // Unsigned (no author) is never accepted.
+        assert!(!MessageBody::unsigned(None, "hi".to_string()).verify());
+    }
+
+    #[test]
+    fn seen_set_drops_replays_but_admits_reorders() {
+        let mut seen = SeenSet::new(256);
+        // Fresh sequences are accepted, including ones that arrive out of order.
+        assert!(seen.observe(0));
+        assert!(seen.observe(3));
+        assert!(seen.observe(4));
+        assert!(seen.observe(1));
+        assert!(seen.observe(2));
+        // Anything already in the window is rejected as a replay.
+        assert!(!seen.observe(3));
+        assert!(!seen.observe(0));
+    }
+
+    #[test]
+    fn seen_set_forgets_beyond_its_capacity() {
+        let mut seen = SeenSet::new(2);
+        assert!(seen.observe(0));
+        assert!(seen.observe(1));
+        // Observing a third seq evicts the oldest, so it is accepted again.
+        assert!(seen.observe(2));
+        assert!(seen.observe(0));
+    }
+
+    #[test]
+    fn query_memory_returns_latest_in_order() {
+        let secret = SecretKey::from_bytes(&[1u8; 32]);
+        let history = History::new(TopicId::from_bytes([0u8; 32]), 8, None);
+        for seq in 0..5 {
+            history.record(&signed(&secret, seq, &format!("m{seq}")));
+        }
+
+        let latest = history.query_memory(&HistoryRequest::latest(3));
+        let seqs: Vec<u64> = latest.iter().map(|m| m.seq).collect();
+        assert_eq!(seqs, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_irc_handles_prefix_and_trailing() {
+        assert_eq!(
+            parse_irc("PRIVMSG #chan :hello world"),
+            ("PRIVMSG".to_string(), vec!["#chan".to_string(), "hello world".to_string()])
+        );
+        assert_eq!(
+            parse_irc(":nick!user@host PRIVMSG #chan :hi\r\n"),
+            ("PRIVMSG".to_string(), vec!["#chan".to_string(), "hi".to_string()])
+        );
+        assert_eq!(
+            parse_irc("PING :token"),
+            ("PING".to_string(), vec!["token".to_string()])
+        );
+        assert_eq!(
+            parse_irc("nick bob"),
+            ("NICK".to_string(), vec!["bob".to_string()])
+        );
+    }
+
+    #[test]
+    fn frame_round_trips_through_bincode() {
+        let secret = SecretKey::from_bytes(&[5u8; 32]);
+        let frame = Frame::Chat(signed(&secret, 7, "round trip"));
+        let bytes = bincode::serde::encode_to_vec(&frame, BINCODE_CONFIG).unwrap();
+        let (decoded, _): (Frame, _) =
+            bincode::serde::decode_from_slice(&bytes, BINCODE_CONFIG).unwrap();
+        match decoded {
+            Frame::Chat(body) => {
+                assert_eq!(body.seq, 7);
+                assert_eq!(body.text, "round trip");
+                assert!(body.verify());
+            }
+            _ => panic!("expected Chat frame"),
+        }
+    }
+}